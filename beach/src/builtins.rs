@@ -1,18 +1,28 @@
 use std::io::{self, Write};
 use std::cell::RefCell;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::env::current_dir;
 
 use umbrella::BlockNumber;
 use umbrella::device::BlockDevice;
 use umbrella::fs::{INodeFlags, FileSystem, Mount};
 
-use args::{Args, Parse};
+use args::{ArgName, Args, Named, Parse};
+
+#[cfg(feature = "fuse")]
+use fuse_mount;
+
+/// A single entry in the mount table, mirroring a kernel's list of mounts.
+struct MountEntry {
+    source: PathBuf,
+    target: PathBuf,
+    file_system: FileSystem
+}
 
 /// The mutable state that backs a shell (environment variables, current directory, ...)
 pub struct Env {
     current_dir: RefCell<PathBuf>,
-    current_fs:  RefCell<Option<FileSystem>>
+    mounts:      RefCell<Vec<MountEntry>>
 }
 
 impl Env {
@@ -20,7 +30,7 @@ impl Env {
         let dir = current_dir().expect("ERROR: Insufficient permissions to read master process current directory");
         Env {
             current_dir: RefCell::new(dir),
-            current_fs:  RefCell::new(None)
+            mounts:      RefCell::new(Vec::new())
         }
     }
 
@@ -30,22 +40,52 @@ impl Env {
 
     const NO_MOUNT_MSG : &'static str = "ERROR: No file system mounted, try running newfs then mount";
 
+    /// Finds the mount whose target is the longest prefix of `path`, the
+    /// same resolution a kernel does for a path-based syscall.
+    fn longest_prefix_index(mounts: &[MountEntry], path: &Path) -> Option<usize> {
+        mounts.iter()
+            .enumerate()
+            .filter(|(_, mount)| path.starts_with(&mount.target))
+            .max_by_key(|(_, mount)| mount.target.as_os_str().len())
+            .map(|(i, _)| i)
+    }
+
     pub fn with_fs<F>(&self, f: F)
     where F: FnOnce(&mut FileSystem) -> ()
     {
-        match *self.current_fs.borrow_mut() {
-            Some(ref mut fs) => f(fs),
+        self.with_fs_at(&self.current_dir(), f)
+    }
+
+    pub fn with_fs_at<F>(&self, path: &Path, f: F)
+    where F: FnOnce(&mut FileSystem) -> ()
+    {
+        let mut mounts = self.mounts.borrow_mut();
+        match Env::longest_prefix_index(&mounts, path) {
+            Some(i) => f(&mut mounts[i].file_system),
             None => eprintln!("{}", Env::NO_MOUNT_MSG)
         }
     }
 
-    pub fn take_fs<F>(&self, f: F)
+    /// Returns `false` (and mounts nothing) if `target` is already in use,
+    /// since two file systems sharing a mount point would make path
+    /// resolution ambiguous and one of them permanently unreachable.
+    pub fn mount(&self, source: PathBuf, target: PathBuf, file_system: FileSystem) -> bool {
+        let mut mounts = self.mounts.borrow_mut();
+        if mounts.iter().any(|mount| mount.target == target) {
+            return false
+        }
+        mounts.push(MountEntry { source, target, file_system });
+        true
+    }
+
+    pub fn take_fs_at<F>(&self, target: &Path, f: F)
     where F: FnOnce(FileSystem) -> ()
     {
-        let cur_fs = self.current_fs.replace(None);
-        match cur_fs {
-            Some(fs) => f(fs),
-            None => eprintln!("{}", Env::NO_MOUNT_MSG)
+        let mut mounts = self.mounts.borrow_mut();
+        let found = mounts.iter().position(|mount| mount.target == target);
+        match found {
+            Some(i) => f(mounts.remove(i).file_system),
+            None => eprintln!("ERROR: No file system mounted at {:?}", target)
         }
     }
 }
@@ -66,9 +106,15 @@ pub fn cd(env: &Env, args: Args) {
     })
 }
 
+/// Tags the `--block-size` option for `newfs`, e.g. `newfs --block-size 256 <file> <count>`.
+pub enum BlockSize {}
+impl ArgName for BlockSize {
+    fn name() -> &'static str { "block-size" }
+}
+
 pub fn new_fs(_env: &Env, args: Args) {
-    type Parser = Hlist![String, u64, Option<u16>];
-    Parser::parse_explain("newfs", args, |hlist_pat![file_name, block_count, block_size]| {
+    type Parser = Hlist![Named<BlockSize, u16>, String, u64];
+    Parser::parse_explain("newfs", args, |hlist_pat![block_size, file_name, block_count]| {
         match BlockDevice::create(&file_name, block_count, block_size) {
             Ok(device) => {
                 if device.config.block_size < 128 {
@@ -96,8 +142,8 @@ pub fn new_fs(_env: &Env, args: Args) {
 }
 
 pub fn mount(env: &Env, args: Args) {
-    type Parser = Hlist![PathBuf];
-    Parser::parse_explain("mount", args, |hlist_pat![file_name]| {
+    type Parser = Hlist![PathBuf, PathBuf];
+    Parser::parse_explain("mount", args, |hlist_pat![file_name, mount_point]| {
         if ! file_name.exists() {
             eprintln!(
                 "ERROR: The device {0:?} does not exist. Try running 'newfs {0:?} 128' first.",
@@ -112,8 +158,9 @@ pub fn mount(env: &Env, args: Args) {
                         if ! clean_mount {
                             eprintln!("WARNING: The filesystem was not properly unmounted")
                         }
-                        let mut cur_fs = env.current_fs.borrow_mut();
-                        *cur_fs = Some(file_system);
+                        if ! env.mount(file_name, mount_point.clone(), file_system) {
+                            eprintln!("ERROR: {:?} already has a file system mounted on it", mount_point);
+                        }
                     }
                     Err(err) => {
                         eprintln!("ERROR: Could not sync filesystem because {}", err)
@@ -125,6 +172,17 @@ pub fn mount(env: &Env, args: Args) {
     })
 }
 
+pub fn mounts(env: &Env, _args: Args) {
+    for mount in env.mounts.borrow().iter() {
+        println!(
+            "{} on {} ({})",
+            mount.source.display(),
+            mount.target.display(),
+            if mount.file_system.is_dirty() { "dirty" } else { "clean" }
+        );
+    }
+}
+
 pub fn block_map(env: &Env, _args: Args) {
     env.with_fs(|fs| {
         print!("{}", fs.block_map);
@@ -173,6 +231,42 @@ pub fn alloc_inode(env: &Env, args: Args) {
     })
 }
 
+pub fn mknod(env: &Env, args: Args) {
+    type Parser = Hlist![INodeFlags, u32, u32];
+    Parser::parse_explain("mknod", args, |hlist_pat![flags, major, minor]| {
+        env.with_fs(|fs| {
+            match fs.inode_map.alloc_device(flags, major, minor) {
+                Some(block_number) => println!("alloc [{}]", block_number),
+                None => eprintln!("ERROR: mknod requires a chardev or blockdev flag, or there is no room left on device")
+            }
+        })
+    })
+}
+
+pub fn symlink(env: &Env, args: Args) {
+    type Parser = Hlist![PathBuf, String];
+    Parser::parse_explain("symlink", args, |hlist_pat![target, name]| {
+        env.with_fs(|fs| {
+            match fs.inode_map.alloc_symlink(&name, target.to_string_lossy().into_owned()) {
+                Some(block_number) => println!("alloc [{}]", block_number),
+                None => eprintln!("ERROR: No room left on device")
+            }
+        })
+    })
+}
+
+pub fn mkfifo(env: &Env, args: Args) {
+    type Parser = Hlist![String];
+    Parser::parse_explain("mkfifo", args, |hlist_pat![name]| {
+        env.with_fs(|fs| {
+            match fs.inode_map.alloc_fifo(&name) {
+                Some(block_number) => println!("alloc [{}]", block_number),
+                None => eprintln!("ERROR: No room left on device")
+            }
+        })
+    })
+}
+
 pub fn free_inode(env: &Env, args: Args) {
     type Parser = Hlist![BlockNumber];
     Parser::parse_explain("free_inode", args, |hlist_pat![block_number]| {
@@ -182,10 +276,111 @@ pub fn free_inode(env: &Env, args: Args) {
     })
 }
 
-pub fn unmount(env: &Env, _args: Args) {
-    env.take_fs(|fs| {
-        fs.close().unwrap_or_else(|err| {
-            eprintln!("ERROR: File system was not unmounted cleanly because: {}", err)
+pub fn stat(env: &Env, args: Args) {
+    type Parser = Hlist![BlockNumber];
+    Parser::parse_explain("stat", args, |hlist_pat![inode]| {
+        env.with_fs(|fs| {
+            match fs.inode_map.get(inode) {
+                Some(meta) => {
+                    println!("inode:  [{}]", inode);
+                    println!("atime:  {}", meta.atime());
+                    println!("mtime:  {}", meta.mtime());
+                    println!("ctime:  {}", meta.ctime());
+                }
+                None => eprintln!("ERROR: No such inode [{}]", inode)
+            }
+        })
+    })
+}
+
+pub fn touch(env: &Env, args: Args) {
+    type Parser = Hlist![BlockNumber];
+    Parser::parse_explain("touch", args, |hlist_pat![inode]| {
+        env.with_fs(|fs| {
+            if fs.inode_map.touch(inode).is_none() {
+                eprintln!("ERROR: No such inode [{}]", inode)
+            }
+        })
+    })
+}
+
+pub fn setxattr(env: &Env, args: Args) {
+    type Parser = Hlist![BlockNumber, String, String];
+    Parser::parse_explain("setxattr", args, |hlist_pat![inode, name, value]| {
+        env.with_fs(|fs| {
+            match fs.set_xattr(inode, &name, value.into_bytes()) {
+                Ok(()) => {}
+                Err(err) => eprintln!("ERROR: {}", err)
+            }
+        })
+    })
+}
+
+pub fn getxattr(env: &Env, args: Args) {
+    type Parser = Hlist![BlockNumber, String];
+    Parser::parse_explain("getxattr", args, |hlist_pat![inode, name]| {
+        env.with_fs(|fs| {
+            match fs.get_xattr(inode, &name) {
+                Ok(Some(value)) => println!("{}", String::from_utf8_lossy(&value)),
+                Ok(None) => eprintln!("ERROR: No such attribute '{}' on inode [{}]", name, inode),
+                Err(err) => eprintln!("ERROR: {}", err)
+            }
+        })
+    })
+}
+
+pub fn fsck(env: &Env, _args: Args) {
+    env.with_fs(|fs| {
+        let corrupted = fs.fsck();
+        if corrupted.is_empty() {
+            println!("fsck: no corruption found");
+        } else {
+            for (block, err) in corrupted {
+                println!("fsck: block [{}] {}", block, err);
+            }
+        }
+    })
+}
+
+#[cfg(feature = "fuse")]
+pub fn fusemount(env: &Env, args: Args) {
+    type Parser = Hlist![PathBuf, PathBuf];
+    Parser::parse_explain("fusemount", args, |hlist_pat![file_name, mountpoint]| {
+        if ! file_name.exists() {
+            eprintln!(
+                "ERROR: The device {0:?} does not exist. Try running 'newfs {0:?} 128' first.",
+                file_name
+            );
+            return
+        }
+        match BlockDevice::open(file_name.to_string_lossy().as_ref()) {
+            Ok(device) => {
+                match FileSystem::read(device) {
+                    Ok(Mount { clean_mount, file_system }) => {
+                        if ! clean_mount {
+                            eprintln!("WARNING: The filesystem was not properly unmounted")
+                        }
+                        if let Err(err) = fuse_mount::mount(file_system, &mountpoint) {
+                            eprintln!("ERROR: Could not mount via FUSE: {}", err);
+                        }
+                    }
+                    Err(err) => {
+                        eprintln!("ERROR: Could not sync filesystem because {}", err)
+                    }
+                }
+            }
+            Err(err) => eprintln!("ERROR: {}", err)
+        }
+    })
+}
+
+pub fn unmount(env: &Env, args: Args) {
+    type Parser = Hlist![PathBuf];
+    Parser::parse_explain("unmount", args, |hlist_pat![mount_point]| {
+        env.take_fs_at(&mount_point, |fs| {
+            fs.close().unwrap_or_else(|err| {
+                eprintln!("ERROR: File system was not unmounted cleanly because: {}", err)
+            })
         })
     })
 }
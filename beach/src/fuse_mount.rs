@@ -0,0 +1,141 @@
+//! Adapter that exposes a mounted `umbrella::fs::FileSystem` to the host kernel
+//! through FUSE, so ordinary Unix tools can `ls`/`cat` a beach image.
+#![cfg(feature = "fuse")]
+
+use std::cell::RefCell;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use fuser::{
+    FileAttr, FileType, Filesystem, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, ReplyWrite,
+    Request,
+};
+
+use umbrella::block_number::BlockNumber;
+use umbrella::fs::FileSystem as BeachFs;
+
+const TTL: Duration = Duration::from_secs(1);
+
+/// Bridges libfuse callbacks to the crate's inode/block primitives
+/// (`inode_map`, `block_map`, and the block `Cache`).
+pub struct FuseAdapter {
+    fs: RefCell<BeachFs>,
+}
+
+impl FuseAdapter {
+    pub fn new(fs: BeachFs) -> FuseAdapter {
+        FuseAdapter { fs: RefCell::new(fs) }
+    }
+
+    fn attr_for(&self, inode: BlockNumber) -> Option<FileAttr> {
+        let fs = self.fs.borrow();
+        let meta = fs.inode_map.get(inode)?;
+        Some(FileAttr {
+            ino: inode.as_u64(),
+            size: meta.size(),
+            blocks: meta.block_count(),
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind: if meta.is_dir() { FileType::Directory } else { FileType::RegularFile },
+            perm: 0o644,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            blksize: fs.block_size() as u32,
+            flags: 0,
+        })
+    }
+}
+
+impl Filesystem for FuseAdapter {
+    fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let parent = BlockNumber::new(parent);
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => return reply.error(libc::EINVAL),
+        };
+        let mut fs = self.fs.borrow_mut();
+        match fs.lookup_child(parent, name) {
+            Ok(inode) => match self.attr_for(inode) {
+                Some(attr) => reply.entry(&TTL, &attr, 0),
+                None => reply.error(libc::ENOENT),
+            },
+            Err(_) => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
+        match self.attr_for(BlockNumber::new(ino)) {
+            Some(attr) => reply.attr(&TTL, &attr),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let mut fs = self.fs.borrow_mut();
+        let entries = match fs.readdir(BlockNumber::new(ino)) {
+            Ok(entries) => entries,
+            Err(_) => return reply.error(libc::ENOENT),
+        };
+        for (i, entry) in entries.into_iter().enumerate().skip(offset as usize) {
+            let kind = if entry.is_dir { FileType::Directory } else { FileType::RegularFile };
+            if reply.add(entry.inode.as_u64(), (i + 1) as i64, kind, entry.name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let mut fs = self.fs.borrow_mut();
+        match fs.read_data(BlockNumber::new(ino), offset as u64, size as usize) {
+            Ok(data) => reply.data(&data),
+            Err(_) => reply.error(libc::EIO),
+        }
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        let mut fs = self.fs.borrow_mut();
+        match fs.write(BlockNumber::new(ino), offset as u64, data) {
+            Ok(written) => reply.written(written as u32),
+            Err(_) => reply.error(libc::EIO),
+        }
+    }
+}
+
+pub fn mount(fs: BeachFs, mountpoint: &Path) -> std::io::Result<()> {
+    let adapter = FuseAdapter::new(fs);
+    fuser::mount2(adapter, mountpoint, &[])
+}
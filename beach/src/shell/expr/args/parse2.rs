@@ -30,34 +30,79 @@ impl<E> From<E> for Err<E> {
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub struct Args<'a> {
     ptr: usize,
-    vec: Vec<&'a str>
+    vec: Vec<&'a str>,
+    taken: Vec<bool>
 }
 
 impl<'a> Args<'a> {
     pub fn new<S>(args: &'a [S]) -> Args<'a>
     where S: AsRef<str>
     {
-        Args {
-            ptr: 0,
-            vec: args.iter().map(|s| s.as_ref()).collect()
-        }
+        let vec: Vec<&'a str> = args.iter().map(|s| s.as_ref()).collect();
+        let taken = vec![false; vec.len()];
+        Args { ptr: 0, vec, taken }
+    }
+
+    fn is_flag_token(s: &str) -> bool {
+        s.starts_with("--") || (s.starts_with('-') && s.len() == 2)
     }
 
+    /// Pops the next *positional* token, skipping tokens a `Flag`/`Named`
+    /// has already claimed and any other `--flag`/`-f` token. The value
+    /// after a `--name` token that is not yet claimed is not skipped: it is
+    /// returned as a positional.
     pub fn pop<E>(&mut self) -> Result<&'a str, Err<E>> {
-        if self.ptr < self.vec.len() {
-            let res = Ok(self.vec[self.ptr]);
+        while self.ptr < self.vec.len() {
+            if self.taken[self.ptr] || Args::is_flag_token(self.vec[self.ptr]) {
+                self.ptr += 1;
+                continue;
+            }
+            let res = self.vec[self.ptr];
             self.ptr += 1;
-            res
-        } else {
-            Err(Err::MissingArgument)
+            return Ok(res);
+        }
+        Err(Err::MissingArgument)
+    }
+
+    /// Claims a boolean `--name` toggle from anywhere in the argument list.
+    pub fn take_flag(&mut self, name: &str) -> bool {
+        let long = format!("--{}", name);
+        for i in 0..self.vec.len() {
+            if !self.taken[i] && self.vec[i] == long {
+                self.taken[i] = true;
+                return true;
+            }
         }
+        false
     }
+
+    /// Claims a `--name value` pair from anywhere in the argument list.
+    pub fn take_named(&mut self, name: &str) -> Option<&'a str> {
+        let long = format!("--{}", name);
+        for i in 0..self.vec.len() {
+            if !self.taken[i] && self.vec[i] == long && i + 1 < self.vec.len() {
+                self.taken[i] = true;
+                self.taken[i + 1] = true;
+                return Some(self.vec[i + 1]);
+            }
+        }
+        None
+    }
+}
+
+/// Gives a zero-sized marker type (`pub enum BlockSize {}`) the flag name
+/// used on the command line, e.g. `--block-size`.
+pub trait ArgName {
+    fn name() -> &'static str;
 }
 
 pub trait ParseArg {
     type Arg;
     type Err;
     fn parse_arg(args: &mut Args) -> Result<Self::Arg, Err<Self::Err>>;
+    fn describe() -> String {
+        "<arg>".to_string()
+    }
 }
 
 pub enum Nat {}
@@ -70,6 +115,10 @@ impl ParseArg for Nat {
         let res = u8::from_str(arg)?;
         Ok(res)
     }
+
+    fn describe() -> String {
+        "<nat>".to_string()
+    }
 }
 
 pub enum Text {}
@@ -80,6 +129,50 @@ impl ParseArg for Text {
     fn parse_arg(args: &mut Args) -> Result<Self::Arg, Err<Self::Err>> {
         args.pop().map(String::from)
     }
+
+    fn describe() -> String {
+        "<text>".to_string()
+    }
+}
+
+impl ParseArg for String {
+    type Arg = String;
+    type Err = Void;
+    fn parse_arg(args: &mut Args) -> Result<Self::Arg, Err<Self::Err>> {
+        args.pop().map(String::from)
+    }
+
+    fn describe() -> String {
+        "<text>".to_string()
+    }
+}
+
+impl ParseArg for u64 {
+    type Arg = u64;
+    type Err = <u64 as FromStr>::Err;
+    fn parse_arg(args: &mut Args) -> Result<Self::Arg, Err<Self::Err>> {
+        let arg = args.pop()?;
+        let res = u64::from_str(arg)?;
+        Ok(res)
+    }
+
+    fn describe() -> String {
+        "<nat>".to_string()
+    }
+}
+
+impl ParseArg for u16 {
+    type Arg = u16;
+    type Err = <u16 as FromStr>::Err;
+    fn parse_arg(args: &mut Args) -> Result<Self::Arg, Err<Self::Err>> {
+        let arg = args.pop()?;
+        let res = u16::from_str(arg)?;
+        Ok(res)
+    }
+
+    fn describe() -> String {
+        "<nat>".to_string()
+    }
 }
 
 pub struct Optional<A> {
@@ -100,13 +193,99 @@ impl<A: ParseArg> ParseArg for Optional<A> {
         };
         Ok(res)
     }
+
+    fn describe() -> String {
+        format!("[{}]", A::describe())
+    }
 }
 
+/// A boolean `--name` toggle, true if present anywhere in the arguments.
+pub struct Flag<Name> {
+    _phantom: PhantomData<Name>
+}
+
+impl<Name: ArgName> ParseArg for Flag<Name> {
+    type Arg = bool;
+    type Err = Void;
+    fn parse_arg(args: &mut Args) -> Result<Self::Arg, Err<Self::Err>> {
+        Ok(args.take_flag(Name::name()))
+    }
+
+    fn describe() -> String {
+        format!("[--{}]", Name::name())
+    }
+}
+
+/// A `--name value` option, parsed with `A` if present.
+pub struct Named<Name, A> {
+    _phantom: PhantomData<(Name, A)>
+}
+
+impl<Name: ArgName, A: ParseArg> ParseArg for Named<Name, A> {
+    type Arg = Option<A::Arg>;
+    type Err = A::Err;
+    fn parse_arg(args: &mut Args) -> Result<Self::Arg, Err<Self::Err>> {
+        match args.take_named(Name::name()) {
+            Some(value) => {
+                let mut sub_args = Args::new(&[value]);
+                A::parse_arg(&mut sub_args).map(Some)
+            }
+            None => Ok(None)
+        }
+    }
+
+    fn describe() -> String {
+        format!("[--{} {}]", Name::name(), A::describe())
+    }
+}
+
+/// Greedily consumes zero-or-more trailing positional `A`s.
+pub struct Many<A> {
+    _phantom: PhantomData<A>
+}
+
+impl<A: ParseArg> ParseArg for Many<A> {
+    type Arg = Vec<A::Arg>;
+    type Err = A::Err;
+    fn parse_arg(args: &mut Args) -> Result<Self::Arg, Err<Self::Err>> {
+        let mut result = Vec::new();
+        loop {
+            let old_ptr = args.ptr;
+            match A::parse_arg(args) {
+                Ok(arg) => result.push(arg),
+                Err(_) => {
+                    args.ptr = old_ptr;
+                    break;
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    fn describe() -> String {
+        format!("{}...", A::describe())
+    }
+}
 
 pub trait Parse: HList {
     type List;
     type Err;
     fn parse(args: Args) -> Result<Self::List, Err<Self::Err>>;
+    fn usage() -> Vec<String>;
+
+    fn parse_explain<F>(name: &str, args: Args, f: F)
+    where F: FnOnce(Self::List)
+    {
+        match Self::parse(args) {
+            Ok(list) => f(list),
+            Err(Err::MissingArgument) => {
+                eprintln!("usage: {} {}", name, Self::usage().join(" "));
+            }
+            Err(Err::Other(_)) => {
+                eprintln!("ERROR: could not parse the arguments to '{}'", name);
+            }
+        }
+    }
 }
 
 impl Parse for HNil {
@@ -115,6 +294,10 @@ impl Parse for HNil {
     fn parse(_: Args) -> Result<Self::List, Err<Self::Err>> {
         Ok(HNil)
     }
+
+    fn usage() -> Vec<String> {
+        Vec::new()
+    }
 }
 
 impl<H: ParseArg, T: Parse> Parse for HCons<H, T> {
@@ -125,11 +308,28 @@ impl<H: ParseArg, T: Parse> Parse for HCons<H, T> {
         let tail = T::parse(args).map_err(|err| err.map(Coproduct::Inr))?;
         Ok(HCons { head, tail })
     }
+
+    fn usage() -> Vec<String> {
+        let mut usage = vec![H::describe()];
+        usage.extend(T::usage());
+        usage
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    pub enum BlockSize {}
+    impl ArgName for BlockSize {
+        fn name() -> &'static str { "block-size" }
+    }
+
+    pub enum Verbose {}
+    impl ArgName for Verbose {
+        fn name() -> &'static str { "verbose" }
+    }
+
     #[test]
     fn parse_none() {
         let vec : Vec<&str> = vec![];
@@ -146,4 +346,24 @@ mod tests {
             Ok(hlist!["foobar".to_string(), 2, None, "not-a-number".to_string()])
         );
     }
+
+    #[test]
+    fn parse_flag_and_named_ignore_position() {
+        let vec = vec!["foobar", "--verbose", "--block-size", "200"];
+        let args = Args::new(&vec);
+        assert_eq!(
+            <Hlist![Text, Flag<Verbose>, Named<BlockSize, Nat>]>::parse(args),
+            Ok(hlist!["foobar".to_string(), true, Some(200)])
+        );
+    }
+
+    #[test]
+    fn parse_trailing_many() {
+        let vec = vec!["1", "2", "3"];
+        let args = Args::new(&vec);
+        assert_eq!(
+            <Hlist![Many<Nat>]>::parse(args),
+            Ok(hlist![vec![1, 2, 3]])
+        );
+    }
 }
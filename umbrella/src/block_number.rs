@@ -0,0 +1,22 @@
+use core::fmt;
+
+/// An index into a `BlockDevice`, newtyped so it can't be confused with a
+/// raw byte offset or inode number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlockNumber(u64);
+
+impl BlockNumber {
+    pub fn new(n: u64) -> BlockNumber {
+        BlockNumber(n)
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for BlockNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
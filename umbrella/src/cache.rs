@@ -1,28 +1,54 @@
-use std::mem;
-use std::cell::{RefCell, Ref, RefMut};
-use std::collections::hash_map::{HashMap, Entry};
+use core::mem;
+use core::cell::{Cell, RefCell, Ref, RefMut};
+
+#[cfg(not(feature = "std"))]
+use alloc::rc::Rc;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+#[cfg(not(feature = "std"))]
+use alloc::vec;
+#[cfg(feature = "std")]
 use std::rc::Rc;
 
+use indexmap::IndexMap;
+
 use block_number::{BlockNumber};
+use checksum::ChecksumMap;
 use device::{self, BlockDevice, Error};
 
 #[derive(Clone)]
 pub struct SharedVec<T> {
-    pub vec: Rc<RefCell<Vec<T>>>
+    pub vec: Rc<RefCell<Vec<T>>>,
+    dirty: Rc<Cell<bool>>
 }
 
 impl<T> SharedVec<T> {
     pub fn new(vec: Vec<T>) -> SharedVec<T> {
-        SharedVec { vec: Rc::new(RefCell::new(vec)) }
+        SharedVec { vec: Rc::new(RefCell::new(vec)), dirty: Rc::new(Cell::new(false)) }
     }
 
     pub fn borrow(&self) -> Ref<Vec<T>> {
         self.vec.borrow()
     }
 
+    /// Any caller reaching for a mutable view is assumed to be about to
+    /// change the block, so this marks it dirty ahead of the actual write.
     pub fn borrow_mut(&self) -> RefMut<Vec<T>> {
+        self.dirty.set(true);
         self.vec.borrow_mut()
     }
+
+    fn is_dirty(&self) -> bool {
+        self.dirty.get()
+    }
+
+    fn mark_dirty(&self) {
+        self.dirty.set(true)
+    }
+
+    fn mark_clean(&self) {
+        self.dirty.set(false)
+    }
 }
 
 pub enum CacheEntry {
@@ -88,94 +114,160 @@ impl CacheEntry {
             }
         }
     }
+
+    fn is_dirty(&self) -> bool {
+        use self::CacheEntry::*;
+        match *self {
+            Block { ref block } => block.is_dirty(),
+            Pointers { ref pointers } => pointers.is_dirty()
+        }
+    }
+
+    fn mark_clean(&self) {
+        use self::CacheEntry::*;
+        match *self {
+            Block { ref block } => block.mark_clean(),
+            Pointers { ref pointers } => pointers.mark_clean()
+        }
+    }
 }
 
 pub struct Cache {
-    pub (crate) entries: HashMap<BlockNumber, CacheEntry>,
-    pub (crate) device:  BlockDevice
+    pub (crate) entries:   IndexMap<BlockNumber, CacheEntry>,
+    pub (crate) device:    BlockDevice,
+    pub (crate) checksums: ChecksumMap
 }
 
 impl Cache {
-    pub fn new(device: BlockDevice) -> Cache {
-        Cache {
-            entries: HashMap::new(),
-            device
+    pub fn new(mut device: BlockDevice) -> device::Result<Cache> {
+        let checksums = ChecksumMap::load(&mut device)?;
+        Ok(Cache {
+            entries: IndexMap::new(),
+            device,
+            checksums
+        })
+    }
+
+    /// Moves `block_num` to the back of the map so the front stays ordered
+    /// from least- to most-recently-used.
+    fn touch(&mut self, block_num: BlockNumber) {
+        if let Some(idx) = self.entries.get_index_of(&block_num) {
+            let last = self.entries.len() - 1;
+            self.entries.move_index(idx, last);
         }
     }
 
+    /// Drops the least-recently-used *clean* block once the cache is over
+    /// capacity, flushing the oldest dirty block first if every entry is
+    /// currently dirty.
+    fn evict_if_needed(&mut self) -> device::Result<()> {
+        let capacity = self.device.config.cache_capacity;
+        while self.entries.len() > capacity {
+            let clean_victim = self.entries.iter().position(|(_, entry)| !entry.is_dirty());
+            if let Some(idx) = clean_victim {
+                self.entries.shift_remove_index(idx);
+                continue;
+            }
+            let (block_num, mut bytes) = match self.entries.get_index(0) {
+                Some((block_num, entry)) => (*block_num, entry.bytes()),
+                None => break
+            };
+            self.device.write(block_num, &mut bytes)?;
+            self.checksums.set(block_num, &bytes);
+            if let Some((_, entry)) = self.entries.get_index(0) {
+                entry.mark_clean();
+            }
+        }
+        Ok(())
+    }
+
     pub fn read(&mut self, block_num: BlockNumber) -> device::Result<SharedVec<u8>> {
         use self::CacheEntry::*;
         let block_size = self.device.config.block_size as usize;
-        let Cache { ref mut entries, ref mut device } = *self;
-        match entries.entry(block_num) {
-            Entry::Occupied(o) => {
-                match *o.get() {
-                    Block { ref block } => {
-                        Ok(block.clone())
-                    }
-                    Pointers { .. } => {
-                        Err(Error::CacheInvalid)
-                    }
-                }
+        let cached = {
+            let Cache { ref mut entries, .. } = *self;
+            match entries.get(&block_num) {
+                Some(Block { ref block }) => Some(Ok(block.clone())),
+                Some(Pointers { .. }) => Some(Err(Error::CacheInvalid)),
+                None => None
             }
-            Entry::Vacant(v) => {
+        };
+        let result = match cached {
+            Some(result) => result?,
+            None => {
                 let mut block = vec![0; block_size];
-                device.read(block_num, &mut block)?;
+                self.device.read(block_num, &mut block)?;
+                self.checksums.verify(block_num, &block)?;
                 let vec = SharedVec::new(block);
-                let cache_entry = Block { block: vec.clone() };
-                v.insert(cache_entry);
-                Ok(vec)
+                self.entries.insert(block_num, Block { block: vec.clone() });
+                vec
             }
-        }
+        };
+        self.touch(block_num);
+        self.evict_if_needed()?;
+        Ok(result)
     }
 
     pub fn read_pointers(&mut self, block_num: BlockNumber)
                          -> device::Result<SharedVec<BlockNumber>> {
         use self::CacheEntry::*;
         let block_size = self.device.config.block_size as usize;
-        let Cache { ref mut entries, ref mut device } = *self;
-        match entries.entry(block_num) {
-            Entry::Occupied(o) => {
-                match *o.get() {
-                    Block { .. } => {
-                        Err(Error::CacheInvalid)
-                    }
-                    Pointers { ref pointers } => {
-                        Ok(pointers.clone())
-                    }
-                }
+        let cached = {
+            let Cache { ref mut entries, .. } = *self;
+            match entries.get(&block_num) {
+                Some(Block { .. }) => Some(Err(Error::CacheInvalid)),
+                Some(Pointers { ref pointers }) => Some(Ok(pointers.clone())),
+                None => None
             }
-            Entry::Vacant(v) => {
+        };
+        let result = match cached {
+            Some(result) => result?,
+            None => {
                 let mut block = vec![0; block_size];
-                device.read(block_num, &mut block)?;
+                self.device.read(block_num, &mut block)?;
+                self.checksums.verify(block_num, &block)?;
                 let pointers = unsafe {
                     // LAST-AUDIT: mckean.kylej@gmail.com 01-05-18
                     from_u8(block)
                 };
                 let vec = SharedVec::new(pointers);
-                let cache_entry = Pointers { pointers: vec.clone() };
-                v.insert(cache_entry);
-                Ok(vec)
+                self.entries.insert(block_num, Pointers { pointers: vec.clone() });
+                vec
             }
-        }
+        };
+        self.touch(block_num);
+        self.evict_if_needed()?;
+        Ok(result)
     }
 
     pub fn write_pointers(&mut self, block_num: BlockNumber, pointers: Vec<BlockNumber>) {
         use self::CacheEntry::*;
-        let ps = Pointers { pointers: SharedVec::new(pointers) };
-        self.entries.insert(block_num, ps);
+        let vec = SharedVec::new(pointers);
+        vec.mark_dirty();
+        self.entries.insert(block_num, Pointers { pointers: vec });
+        self.touch(block_num);
     }
 
     pub fn write_all(&mut self) -> device::Result<()> {
         for (block_number, cache_entry) in &self.entries {
-            self.device.write(*block_number, &mut cache_entry.bytes())?
+            if cache_entry.is_dirty() {
+                let mut bytes = cache_entry.bytes();
+                self.device.write(*block_number, &mut bytes)?;
+                self.checksums.set(*block_number, &bytes);
+            }
         }
-        Ok(())
+        for (_, cache_entry) in &self.entries {
+            cache_entry.mark_clean();
+        }
+        self.checksums.flush(&mut self.device)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use device::{BlockIo, Config};
+
     #[test]
     fn as_u8() {
         let vec : Vec<u64> = vec![0, 1, 2u64.pow(20) - 1, 3];
@@ -184,4 +276,53 @@ mod tests {
         };
         assert_eq!(vec, bytes);
     }
+
+    struct MemBackend {
+        blocks: Vec<Vec<u8>>
+    }
+
+    impl BlockIo for MemBackend {
+        fn read_block(&mut self, block: BlockNumber, buf: &mut [u8]) -> device::Result<()> {
+            buf.copy_from_slice(&self.blocks[block.as_u64() as usize]);
+            Ok(())
+        }
+
+        fn write_block(&mut self, block: BlockNumber, buf: &[u8]) -> device::Result<()> {
+            self.blocks[block.as_u64() as usize].copy_from_slice(buf);
+            Ok(())
+        }
+
+        // Accept (and drop) the checksum region so `write_all` can succeed.
+        fn write_region(&mut self, _offset: u64, _buf: &[u8]) -> device::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn mem_device(block_count: u64, block_size: u16, cache_capacity: usize) -> BlockDevice {
+        let config = Config { block_size, block_count, cache_capacity };
+        let backend = MemBackend { blocks: vec![vec![0; block_size as usize]; block_count as usize] };
+        BlockDevice::new(config, Box::new(backend))
+    }
+
+    /// Regression test for a bug where flushing a dirty victim during forced
+    /// eviction never updated its checksum, so the next read of that block
+    /// raised a spurious `Error::ChecksumMismatch`.
+    #[test]
+    fn evict_dirty_block_keeps_checksum_valid() {
+        let device = mem_device(2, 8, 2);
+        let mut cache = Cache::new(device).unwrap();
+
+        // Establish a checksum for block 0's first write, then dirty it
+        // again with different contents before it's ever flushed again.
+        cache.read(BlockNumber::new(0)).unwrap().borrow_mut()[0] = 1;
+        cache.write_all().unwrap();
+        cache.read(BlockNumber::new(0)).unwrap().borrow_mut()[0] = 2;
+
+        // Force the cache to evict the lone, still-dirty entry.
+        cache.device.config.cache_capacity = 0;
+        cache.evict_if_needed().unwrap();
+
+        let block = cache.read(BlockNumber::new(0)).unwrap();
+        assert_eq!(block.borrow()[0], 2);
+    }
 }
@@ -0,0 +1,44 @@
+use hashbrown::HashMap;
+
+use crc32c::crc32c;
+
+use block_number::BlockNumber;
+use device::{self, BlockDevice};
+
+/// Per-block CRC32C checksums, indexed by `BlockNumber`, persisted in the
+/// device's dedicated checksum region of the superblock.
+pub struct ChecksumMap {
+    checksums: HashMap<BlockNumber, u32>
+}
+
+impl ChecksumMap {
+    pub fn load(device: &mut BlockDevice) -> device::Result<ChecksumMap> {
+        let checksums = device.read_checksums()?;
+        Ok(ChecksumMap { checksums })
+    }
+
+    pub fn verify(&self, block_num: BlockNumber, bytes: &[u8]) -> device::Result<()> {
+        match self.checksums.get(&block_num) {
+            Some(&expected) => {
+                let found = crc32c(bytes);
+                if found != expected {
+                    return Err(device::Error::ChecksumMismatch { block: block_num, expected, found })
+                }
+                Ok(())
+            }
+            None => Ok(())
+        }
+    }
+
+    pub fn set(&mut self, block_num: BlockNumber, bytes: &[u8]) {
+        self.checksums.insert(block_num, crc32c(bytes));
+    }
+
+    pub fn get(&self, block_num: BlockNumber) -> Option<u32> {
+        self.checksums.get(&block_num).cloned()
+    }
+
+    pub fn flush(&self, device: &mut BlockDevice) -> device::Result<()> {
+        device.write_checksums(&self.checksums)
+    }
+}
@@ -0,0 +1,245 @@
+use core::fmt;
+
+#[cfg(not(feature = "std"))]
+use alloc::boxed::Box;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+#[cfg(not(feature = "std"))]
+use alloc::vec;
+
+use hashbrown::HashMap;
+
+#[cfg(feature = "std")]
+use std::fs::{File, OpenOptions};
+#[cfg(feature = "std")]
+use std::io::{Read, Seek, SeekFrom, Write};
+#[cfg(feature = "std")]
+use std::path::Path;
+
+use block_number::BlockNumber;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Config {
+    pub block_size: u16,
+    pub block_count: u64,
+    pub cache_capacity: usize
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    CacheInvalid,
+    ChecksumMismatch { block: BlockNumber, expected: u32, found: u32 },
+    OutOfBounds { block: BlockNumber },
+    BackendFailure
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::CacheInvalid => write!(f, "cache entry held the wrong block type"),
+            Error::ChecksumMismatch { block, expected, found } => write!(
+                f, "checksum mismatch on block [{}]: expected {:#010x}, found {:#010x}",
+                block, expected, found
+            ),
+            Error::OutOfBounds { block } => write!(f, "block [{}] is out of bounds", block),
+            Error::BackendFailure => write!(f, "the block backend failed")
+        }
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Bytes reserved at the front of the device for `Superblock`'s fixed
+/// fields (block size, block count), ahead of the checksum bitmap/array.
+const SUPERBLOCK_HEADER_LEN: u64 = 10;
+
+/// Lays out the superblock that precedes the block-addressed data region:
+/// a fixed header (block size, block count) followed by a one-bit-per-block
+/// "has a checksum" bitmap and a dense `u32` checksum array. Sizing the
+/// bitmap/array off `block_count` (rather than the live checksum count)
+/// keeps every block's slot at a fixed offset, so it doesn't need to move
+/// as entries are added or removed.
+struct Superblock {
+    block_size: u16,
+    block_count: u64
+}
+
+impl Superblock {
+    fn bitmap_len(&self) -> u64 {
+        (self.block_count + 7) / 8
+    }
+
+    fn checksum_array_len(&self) -> u64 {
+        self.block_count * 4
+    }
+
+    /// Where the block-addressed data region begins.
+    fn data_offset(&self) -> u64 {
+        SUPERBLOCK_HEADER_LEN + self.bitmap_len() + self.checksum_array_len()
+    }
+
+    fn encode_header(&self) -> [u8; SUPERBLOCK_HEADER_LEN as usize] {
+        let mut header = [0u8; SUPERBLOCK_HEADER_LEN as usize];
+        header[0..2].copy_from_slice(&self.block_size.to_le_bytes());
+        header[2..10].copy_from_slice(&self.block_count.to_le_bytes());
+        header
+    }
+
+    fn decode_header(header: &[u8; SUPERBLOCK_HEADER_LEN as usize]) -> Superblock {
+        let block_size = u16::from_le_bytes([header[0], header[1]]);
+        let mut block_count_bytes = [0u8; 8];
+        block_count_bytes.copy_from_slice(&header[2..10]);
+        let block_count = u64::from_le_bytes(block_count_bytes);
+        Superblock { block_size, block_count }
+    }
+
+    fn encode_checksums(&self, checksums: &HashMap<BlockNumber, u32>) -> (Vec<u8>, Vec<u8>) {
+        let mut bitmap = vec![0u8; self.bitmap_len() as usize];
+        let mut array = vec![0u8; self.checksum_array_len() as usize];
+        for (block, checksum) in checksums {
+            let idx = block.as_u64() as usize;
+            bitmap[idx / 8] |= 1 << (idx % 8);
+            array[idx * 4..idx * 4 + 4].copy_from_slice(&checksum.to_le_bytes());
+        }
+        (bitmap, array)
+    }
+
+    fn decode_checksums(&self, bitmap: &[u8], array: &[u8]) -> HashMap<BlockNumber, u32> {
+        let mut checksums = HashMap::new();
+        for idx in 0..self.block_count as usize {
+            if bitmap[idx / 8] & (1 << (idx % 8)) != 0 {
+                let mut bytes = [0u8; 4];
+                bytes.copy_from_slice(&array[idx * 4..idx * 4 + 4]);
+                checksums.insert(BlockNumber::new(idx as u64), u32::from_le_bytes(bytes));
+            }
+        }
+        checksums
+    }
+}
+
+/// A block-addressable storage backend. A `no_std` consumer (a RAM disk, an
+/// ATA driver) implements this directly instead of going through `std::io`;
+/// the `std` feature supplies a file-backed implementation for free.
+pub trait BlockIo {
+    fn read_block(&mut self, block: BlockNumber, buf: &mut [u8]) -> Result<()>;
+    fn write_block(&mut self, block: BlockNumber, buf: &[u8]) -> Result<()>;
+
+    /// Raw byte-addressed I/O into the superblock region ahead of the
+    /// block-addressed data. Backends with nowhere durable to put it (a
+    /// bare RAM disk with no backing store across reloads) can leave the
+    /// default, which simply reports failure; `Cache::write_all` then
+    /// returns that failure after writing the dirty blocks.
+    fn read_region(&mut self, _offset: u64, _buf: &mut [u8]) -> Result<()> {
+        Err(Error::BackendFailure)
+    }
+
+    fn write_region(&mut self, _offset: u64, _buf: &[u8]) -> Result<()> {
+        Err(Error::BackendFailure)
+    }
+}
+
+#[cfg(feature = "std")]
+struct FileBackend {
+    file: File,
+    data_offset: u64
+}
+
+#[cfg(feature = "std")]
+impl BlockIo for FileBackend {
+    fn read_block(&mut self, block: BlockNumber, buf: &mut [u8]) -> Result<()> {
+        let offset = self.data_offset + block.as_u64() * buf.len() as u64;
+        self.file.seek(SeekFrom::Start(offset)).map_err(|_| Error::BackendFailure)?;
+        self.file.read_exact(buf).map_err(|_| Error::BackendFailure)
+    }
+
+    fn write_block(&mut self, block: BlockNumber, buf: &[u8]) -> Result<()> {
+        let offset = self.data_offset + block.as_u64() * buf.len() as u64;
+        self.file.seek(SeekFrom::Start(offset)).map_err(|_| Error::BackendFailure)?;
+        self.file.write_all(buf).map_err(|_| Error::BackendFailure)
+    }
+
+    fn read_region(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        self.file.seek(SeekFrom::Start(offset)).map_err(|_| Error::BackendFailure)?;
+        self.file.read_exact(buf).map_err(|_| Error::BackendFailure)
+    }
+
+    fn write_region(&mut self, offset: u64, buf: &[u8]) -> Result<()> {
+        self.file.seek(SeekFrom::Start(offset)).map_err(|_| Error::BackendFailure)?;
+        self.file.write_all(buf).map_err(|_| Error::BackendFailure)
+    }
+}
+
+pub struct BlockDevice {
+    pub config: Config,
+    checksums: HashMap<BlockNumber, u32>,
+    io: Box<dyn BlockIo>
+}
+
+impl BlockDevice {
+    pub fn new(config: Config, io: Box<dyn BlockIo>) -> BlockDevice {
+        BlockDevice { config, checksums: HashMap::new(), io }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn create<P: AsRef<Path>>(path: P, block_count: u64, block_size: Option<u16>) -> Result<BlockDevice> {
+        let block_size = block_size.unwrap_or(512);
+        let superblock = Superblock { block_size, block_count };
+        let file = OpenOptions::new()
+            .read(true).write(true).create(true).truncate(true)
+            .open(path)
+            .map_err(|_| Error::BackendFailure)?;
+        let data_offset = superblock.data_offset();
+        file.set_len(data_offset + block_count * block_size as u64).map_err(|_| Error::BackendFailure)?;
+        let config = Config { block_size, block_count, cache_capacity: 1024 };
+        let mut device = BlockDevice::new(config, Box::new(FileBackend { file, data_offset }));
+        device.write_checksums(&HashMap::new())?;
+        Ok(device)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<BlockDevice> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)
+            .map_err(|_| Error::BackendFailure)?;
+        let mut header = [0u8; SUPERBLOCK_HEADER_LEN as usize];
+        file.seek(SeekFrom::Start(0)).map_err(|_| Error::BackendFailure)?;
+        file.read_exact(&mut header).map_err(|_| Error::BackendFailure)?;
+        let on_disk = Superblock::decode_header(&header);
+        let data_offset = on_disk.data_offset();
+
+        let config = Config { block_size: on_disk.block_size, block_count: on_disk.block_count, cache_capacity: 1024 };
+        let mut backend = FileBackend { file, data_offset };
+
+        let mut bitmap = vec![0u8; on_disk.bitmap_len() as usize];
+        backend.read_region(SUPERBLOCK_HEADER_LEN, &mut bitmap)?;
+        let mut array = vec![0u8; on_disk.checksum_array_len() as usize];
+        backend.read_region(SUPERBLOCK_HEADER_LEN + on_disk.bitmap_len(), &mut array)?;
+        let checksums = on_disk.decode_checksums(&bitmap, &array);
+
+        Ok(BlockDevice { config, checksums, io: Box::new(backend) })
+    }
+
+    pub fn read(&mut self, block: BlockNumber, buf: &mut [u8]) -> Result<()> {
+        self.io.read_block(block, buf)
+    }
+
+    pub fn write(&mut self, block: BlockNumber, buf: &mut [u8]) -> Result<()> {
+        self.io.write_block(block, buf)
+    }
+
+    pub fn read_checksums(&mut self) -> Result<HashMap<BlockNumber, u32>> {
+        Ok(self.checksums.clone())
+    }
+
+    /// Persists `checksums` into the superblock's bitmap/array region so
+    /// they survive past this `BlockDevice` (e.g. across an unmount and a
+    /// later `open`), not just for the lifetime of this in-memory map.
+    pub fn write_checksums(&mut self, checksums: &HashMap<BlockNumber, u32>) -> Result<()> {
+        self.checksums = checksums.clone();
+        let superblock = Superblock { block_size: self.config.block_size, block_count: self.config.block_count };
+        self.io.write_region(0, &superblock.encode_header())?;
+        let (bitmap, array) = superblock.encode_checksums(&self.checksums);
+        self.io.write_region(SUPERBLOCK_HEADER_LEN, &bitmap)?;
+        self.io.write_region(SUPERBLOCK_HEADER_LEN + bitmap.len() as u64, &array)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,359 @@
+//! The on-disk filesystem: the block/inode bitmaps, per-inode metadata, and
+//! the directory/data-block walks the shell and the FUSE adapter drive.
+use core::fmt;
+
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+use hashbrown::{HashMap, HashSet};
+
+use block_number::BlockNumber;
+use cache::Cache;
+use device::{self, BlockDevice, Error};
+
+/// Bytes of xattr value kept inline on the inode before spilling to an
+/// overflow block.
+const INLINE_XATTR_LIMIT: usize = 256;
+
+/// Nanoseconds since the Unix epoch, mirroring `st_*time`/`st_*time_nsec`.
+/// There's no clock source without `std`, so a `no_std` build (a RAM disk,
+/// an embedded target) falls back to 0 rather than pulling in a platform
+/// timer dependency.
+#[cfg(feature = "std")]
+fn now() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0)
+}
+
+#[cfg(not(feature = "std"))]
+fn now() -> u64 {
+    0
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum INodeFlags {
+    File,
+    Dir,
+    Symlink,
+    Fifo,
+    CharDevice,
+    BlockDevice
+}
+
+impl fmt::Display for INodeFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            INodeFlags::File => "file",
+            INodeFlags::Dir => "dir",
+            INodeFlags::Symlink => "symlink",
+            INodeFlags::Fifo => "fifo",
+            INodeFlags::CharDevice => "chardev",
+            INodeFlags::BlockDevice => "blockdev"
+        };
+        write!(f, "{}", name)
+    }
+}
+
+struct XAttr {
+    inline: Vec<u8>,
+    overflow: Option<BlockNumber>
+}
+
+pub struct INode {
+    pub flags: INodeFlags,
+    atime: u64,
+    mtime: u64,
+    ctime: u64,
+    size: u64,
+    dev: Option<(u32, u32)>,
+    symlink_target: Option<String>,
+    children: HashMap<String, BlockNumber>,
+    xattrs: HashMap<String, XAttr>
+}
+
+impl INode {
+    fn new(flags: INodeFlags, now: u64) -> INode {
+        INode {
+            flags,
+            atime: now,
+            mtime: now,
+            ctime: now,
+            size: 0,
+            dev: None,
+            symlink_target: None,
+            children: HashMap::new(),
+            xattrs: HashMap::new()
+        }
+    }
+
+    pub fn atime(&self) -> u64 { self.atime }
+    pub fn mtime(&self) -> u64 { self.mtime }
+    pub fn ctime(&self) -> u64 { self.ctime }
+    pub fn size(&self) -> u64 { self.size }
+    pub fn block_count(&self) -> u64 { (self.size + 511) / 512 }
+    pub fn is_dir(&self) -> bool { self.flags == INodeFlags::Dir }
+
+    fn touch_mtime(&mut self, now: u64) {
+        self.mtime = now;
+        self.ctime = now;
+    }
+}
+
+pub struct DirEntry {
+    pub inode: BlockNumber,
+    pub name: String,
+    pub is_dir: bool
+}
+
+pub struct BlockMap {
+    next: u64,
+    free_list: Vec<BlockNumber>,
+    capacity: u64
+}
+
+impl BlockMap {
+    fn new(capacity: u64) -> BlockMap {
+        BlockMap { next: 0, free_list: Vec::new(), capacity }
+    }
+
+    pub fn alloc(&mut self) -> device::Result<BlockNumber> {
+        if let Some(block) = self.free_list.pop() {
+            return Ok(block)
+        }
+        if self.next >= self.capacity {
+            return Err(Error::BackendFailure)
+        }
+        let block = BlockNumber::new(self.next);
+        self.next += 1;
+        Ok(block)
+    }
+
+    pub fn free(&mut self, block: BlockNumber) {
+        self.free_list.push(block)
+    }
+
+    /// Every block handed out by `alloc` and not since `free`d.
+    pub fn allocated(&self) -> Vec<BlockNumber> {
+        let freed: HashSet<BlockNumber> = self.free_list.iter().cloned().collect();
+        (0..self.next).map(BlockNumber::new).filter(|block| !freed.contains(block)).collect()
+    }
+}
+
+impl fmt::Display for BlockMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "allocated: {}, free: {}\n", self.next - self.free_list.len() as u64, self.free_list.len())
+    }
+}
+
+pub struct INodeMap {
+    block_map_capacity: u64,
+    inodes: HashMap<BlockNumber, INode>,
+    next: u64
+}
+
+impl INodeMap {
+    fn new(capacity: u64) -> INodeMap {
+        INodeMap { block_map_capacity: capacity, inodes: HashMap::new(), next: 1 }
+    }
+
+    fn reserve(&mut self) -> Option<BlockNumber> {
+        if self.next >= self.block_map_capacity {
+            return None
+        }
+        let inode = BlockNumber::new(self.next);
+        self.next += 1;
+        Some(inode)
+    }
+
+    pub fn alloc(&mut self, flags: INodeFlags) -> Option<BlockNumber> {
+        let inode_num = self.reserve()?;
+        self.inodes.insert(inode_num, INode::new(flags, now()));
+        Some(inode_num)
+    }
+
+    pub fn alloc_device(&mut self, flags: INodeFlags, major: u32, minor: u32) -> Option<BlockNumber> {
+        if flags != INodeFlags::CharDevice && flags != INodeFlags::BlockDevice {
+            return None
+        }
+        let inode_num = self.reserve()?;
+        let mut inode = INode::new(flags, now());
+        inode.dev = Some((major, minor));
+        self.inodes.insert(inode_num, inode);
+        Some(inode_num)
+    }
+
+    pub fn alloc_symlink(&mut self, _name: &str, target: String) -> Option<BlockNumber> {
+        let inode_num = self.reserve()?;
+        let mut inode = INode::new(INodeFlags::Symlink, now());
+        inode.symlink_target = Some(target);
+        self.inodes.insert(inode_num, inode);
+        Some(inode_num)
+    }
+
+    pub fn alloc_fifo(&mut self, _name: &str) -> Option<BlockNumber> {
+        let inode_num = self.reserve()?;
+        self.inodes.insert(inode_num, INode::new(INodeFlags::Fifo, now()));
+        Some(inode_num)
+    }
+
+    pub fn free(&mut self, block: BlockNumber) {
+        self.inodes.remove(&block);
+    }
+
+    pub fn get(&self, block: BlockNumber) -> Option<&INode> {
+        self.inodes.get(&block)
+    }
+
+    pub fn touch(&mut self, block: BlockNumber) -> Option<()> {
+        let inode = self.inodes.get_mut(&block)?;
+        inode.atime = now();
+        inode.touch_mtime(inode.atime);
+        Some(())
+    }
+
+}
+
+impl fmt::Display for INodeMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "allocated: {}\n", self.inodes.len())
+    }
+}
+
+pub struct Mount {
+    pub clean_mount: bool,
+    pub file_system: FileSystem
+}
+
+pub struct FileSystem {
+    pub block_map: BlockMap,
+    pub inode_map: INodeMap,
+    cache: Cache,
+    dirty: bool
+}
+
+impl FileSystem {
+    pub fn new(device: BlockDevice) -> FileSystem {
+        let capacity = device.config.block_count;
+        let cache = Cache::new(device)
+            .expect("a freshly created device has an empty checksum region and cannot fail to load");
+        FileSystem {
+            block_map: BlockMap::new(capacity),
+            inode_map: INodeMap::new(capacity),
+            cache,
+            dirty: true
+        }
+    }
+
+    pub fn read(device: BlockDevice) -> device::Result<Mount> {
+        let capacity = device.config.block_count;
+        let cache = Cache::new(device)?;
+        let file_system = FileSystem {
+            block_map: BlockMap::new(capacity),
+            inode_map: INodeMap::new(capacity),
+            cache,
+            dirty: false
+        };
+        Ok(Mount { clean_mount: true, file_system })
+    }
+
+    pub fn close(mut self) -> device::Result<()> {
+        self.cache.write_all()
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn block_size(&self) -> u16 {
+        self.cache.device.config.block_size
+    }
+
+    pub fn fsck(&mut self) -> Vec<(BlockNumber, Error)> {
+        let mut corrupted = Vec::new();
+        for block in self.block_map.allocated() {
+            if let Err(err) = self.cache.read(block) {
+                corrupted.push((block, err));
+            }
+        }
+        corrupted
+    }
+
+    pub fn lookup_child(&mut self, parent: BlockNumber, name: &str) -> device::Result<BlockNumber> {
+        self.inode_map.inodes.get(&parent)
+            .and_then(|inode| inode.children.get(name).cloned())
+            .ok_or(Error::OutOfBounds { block: parent })
+    }
+
+    pub fn readdir(&mut self, inode: BlockNumber) -> device::Result<Vec<DirEntry>> {
+        let parent = self.inode_map.inodes.get(&inode).ok_or(Error::OutOfBounds { block: inode })?;
+        Ok(parent.children.iter().map(|(name, child)| {
+            let is_dir = self.inode_map.inodes.get(child).map_or(false, |i| i.is_dir());
+            DirEntry { inode: *child, name: name.clone(), is_dir }
+        }).collect())
+    }
+
+    pub fn read_data(&mut self, inode: BlockNumber, offset: u64, size: usize) -> device::Result<Vec<u8>> {
+        let block_size = self.block_size() as u64;
+        let first_block = BlockNumber::new(inode.as_u64() + offset / block_size);
+        let shared = self.cache.read(first_block)?;
+        let bytes = shared.borrow();
+        let start = (offset % block_size) as usize;
+        let end = (start + size).min(bytes.len());
+        Ok(bytes[start..end].to_vec())
+    }
+
+    pub fn write(&mut self, inode: BlockNumber, offset: u64, data: &[u8]) -> device::Result<usize> {
+        let block_size = self.block_size() as u64;
+        let first_block = BlockNumber::new(inode.as_u64() + offset / block_size);
+        let shared = self.cache.read(first_block)?;
+        {
+            let mut bytes = shared.borrow_mut();
+            let start = (offset % block_size) as usize;
+            let end = (start + data.len()).min(bytes.len());
+            let written = end - start;
+            bytes[start..end].copy_from_slice(&data[..written]);
+        }
+        if let Some(meta) = self.inode_map.inodes.get_mut(&inode) {
+            meta.size = meta.size.max(offset + data.len() as u64);
+            meta.touch_mtime(now());
+        }
+        self.dirty = true;
+        Ok(data.len())
+    }
+
+    /// Stores `value` on `inode`'s xattr list, inline if it fits in
+    /// `INLINE_XATTR_LIMIT` or else in a freshly allocated overflow block.
+    pub fn set_xattr(&mut self, inode: BlockNumber, name: &str, value: Vec<u8>) -> device::Result<()> {
+        let xattr = if value.len() > INLINE_XATTR_LIMIT {
+            let block = self.block_map.alloc()?;
+            let shared = self.cache.read(block)?;
+            let mut bytes = shared.borrow_mut();
+            let written = value.len().min(bytes.len());
+            bytes[..written].copy_from_slice(&value[..written]);
+            XAttr { inline: Vec::new(), overflow: Some(block) }
+        } else {
+            XAttr { inline: value, overflow: None }
+        };
+        let meta = self.inode_map.inodes.get_mut(&inode).ok_or(Error::OutOfBounds { block: inode })?;
+        meta.xattrs.insert(name.into(), xattr);
+        // Changing metadata (not content) bumps ctime only, matching POSIX
+        // xattr semantics.
+        meta.ctime = now();
+        self.dirty = true;
+        Ok(())
+    }
+
+    pub fn get_xattr(&mut self, inode: BlockNumber, name: &str) -> device::Result<Option<Vec<u8>>> {
+        let meta = self.inode_map.inodes.get(&inode).ok_or(Error::OutOfBounds { block: inode })?;
+        let xattr = match meta.xattrs.get(name) {
+            Some(xattr) => xattr,
+            None => return Ok(None)
+        };
+        match xattr.overflow {
+            Some(block) => Ok(Some(self.cache.read(block)?.borrow().clone())),
+            None => Ok(Some(xattr.inline.clone()))
+        }
+    }
+}
@@ -0,0 +1,23 @@
+//! The umbrella filesystem core: block device, cache, and inode/block
+//! allocation primitives. Built `no_std` by default so it can be embedded
+//! in a kernel; enable the `std` feature to get the file-backed
+//! `BlockDevice` and the `beach` shell on top of it.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+
+#[cfg(all(not(feature = "std"), test))]
+extern crate std;
+
+extern crate hashbrown;
+extern crate indexmap;
+extern crate crc32c;
+
+pub mod block_number;
+pub mod device;
+pub mod cache;
+pub mod checksum;
+pub mod fs;
+
+pub use block_number::BlockNumber;